@@ -15,31 +15,63 @@ extern crate piston;
 use piston::{
     keyboard,
     AssetStore,
+    Clock,
+    ClockQueue,
+    Envelope,
     Game,
+    GameEvent,
     GameWindow,
     GameWindowSDL2,
     GameWindowSettings,
     KeyPressArgs,
+    Oscillator,
+    RingBuffer,
     SoundStream,
-    SoundStreamSettings
+    SoundStreamSettings,
+    Waveform
 };
 
+/// How long a key-triggered note rings out before `note_off` is called,
+/// since this trait has no separate key-release event to hook into.
+const NOTE_HOLD_NS: u64 = 300_000_000;
+
+/// Maps a note-triggering key to the frequency (in Hz) it should play.
+fn key_frequency(key: &keyboard::Key) -> Option<f64> {
+    match *key {
+        keyboard::A => Some(261.63), // C4
+        keyboard::S => Some(293.66), // D4
+        keyboard::D => Some(329.63), // E4
+        keyboard::F => Some(349.23), // F4
+        keyboard::G => Some(392.00), // G4
+        _ => None
+    }
+}
+
 // Structs
 //------------------------------
 
 /// Main application struct.
 pub struct App {
-    /// Channel for sending information to the audio stream.
-    kill_chan: Option<Sender<bool>> // Channel for sending kill message.
+    /// Channel for sending the kill message to the audio stream.
+    kill_chan: Option<Sender<bool>>,
+    /// Channel for sending game events (key presses, etc.) to the audio stream.
+    event_chan: Option<Sender<GameEvent>>
 }
 
 /// The audio is non-blocking and needs it's own struct.
 pub struct AppSoundStream {
-    /// Channel for receiving game events from main game stream.
-    kill_chan: Option<Receiver<bool>>, // Channel for receiving kill message.
+    /// Channel for receiving the kill message from the main game thread.
+    kill_chan: Option<Receiver<bool>>,
+    /// Channel for receiving game events from the main game thread.
+    event_chan: Option<Receiver<GameEvent>>,
     should_exit: bool, // Trigger for closing the stream.
     should_print: bool, // Toggle for printing the sample_rate.
-    buffer: Vec<f32> // Buffer for passing input to output.
+    buffer: RingBuffer, // Lock-free buffer for passing input to output.
+    clock: Clock, // Accumulates update()'s dt into a running generation clock.
+    latency_queue: ClockQueue, // Timestamped input buffers, for measuring round-trip latency.
+    oscillator: Oscillator, // Generates the tone for a key-triggered note.
+    envelope: Envelope, // Shapes the oscillator's amplitude over the note's lifetime.
+    note_hold_ns: Option<u64> // Time remaining before the held note is released, if any.
 }
 
 // Game Method Implementations
@@ -51,18 +83,20 @@ impl Game for App {
     /// If using a SoundStream, it must be created within this method.
     fn load(&mut self, asset_store: &mut AssetStore) {
 
-        // Create a channel for communicating events with the soundstream.
-        // Note: this channel is used for sending InteractiveEvents, but
-        // the same technique could be used here to create custom channels
-        // that can safely send any kind of unique data.
-        let (send, recv) = channel();
-        self.kill_chan = Some(send);
+        // Kill channel: a one-shot signal telling the soundstream to exit.
+        let (kill_send, kill_recv) = channel();
+        self.kill_chan = Some(kill_send);
+
+        // Event channel: arbitrary GameEvents (key presses, mouse moves,
+        // parameter changes) flowing from this thread into the soundstream.
+        let (event_send, event_recv) = channel();
+        self.event_chan = Some(event_send);
 
         // Create the soundstream on it's own thread for non-blocking, real-time audio.
         // "soundstreamer" will setup and iterate soundstream using portaudio.
         spawn(proc() {
             let mut soundstream =
-                AppSoundStream::new(Some(recv)).run(SoundStreamSettings::cd_quality());
+                AppSoundStream::new(Some(kill_recv), Some(event_recv)).run(SoundStreamSettings::cd_quality());
         });
 
     }
@@ -72,13 +106,11 @@ impl Game for App {
         println!("Game thread key: {}", args.key);
     }
 
-    /*
     /// Specify the event sending channel. This must be done if we wish
     /// to send interactive events to the SoundStream.
-    fn get_event_sender(&self) -> Option<Sender<GameEvent<'static>>> {
-        self.stream_chan.clone()
+    fn get_event_sender(&self) -> Option<Sender<GameEvent>> {
+        self.event_chan.clone()
     }
-    */
 }
 
 impl Drop for App {
@@ -96,7 +128,8 @@ impl App {
     /// Creates a new application.
     pub fn new() -> App {
         App {
-            kill_chan: None
+            kill_chan: None,
+            event_chan: None
         }
     }
 }
@@ -113,6 +146,18 @@ impl SoundStream for AppSoundStream {
 
     /// Update (gets called prior to audio_in/audio_out).
     fn update(&mut self, settings: &SoundStreamSettings, dt: u64) {
+        // Resize is a no-op once the buffer already matches the
+        // requested capacity, so this is safe to call every update.
+        self.buffer.resize(settings.buffer_frames);
+        self.clock.tick(dt);
+        if let Some(remaining) = self.note_hold_ns {
+            if dt >= remaining {
+                self.envelope.note_off();
+                self.note_hold_ns = None;
+            } else {
+                self.note_hold_ns = Some(remaining - dt);
+            }
+        }
         if self.should_print {
             let dtsec: f64 = dt as f64 / 1000000000f64;
             println!("Real-time sample rate: {}", (1f64 / dtsec) * settings.frames as f64);
@@ -128,12 +173,33 @@ impl SoundStream for AppSoundStream {
 
     /// AudioInput
     fn audio_in(&mut self, input: &Vec<f32>, settings: &SoundStreamSettings) {
-        self.buffer = input.clone();
+        // Pushed into the ring buffer one sample at a time - no
+        // allocation, and samples are dropped rather than blocking
+        // if audio_out hasn't drained it fast enough.
+        for &sample in input.iter() {
+            self.buffer.insert(sample);
+        }
+        // Also stash a timestamped copy so audio_out can report how
+        // stale the input was by the time it got played back out.
+        self.latency_queue.push(self.clock.total(), input.clone());
     }
 
     /// AudioOutput
     fn audio_out(&mut self, output: &mut Vec<f32>, settings: &SoundStreamSettings) {
-        *output = self.buffer.clone()
+        // Pulled back out one sample at a time; play silence rather
+        // than glitch if the buffer has run dry.
+        for out_sample in output.iter_mut() {
+            let passthrough = self.buffer.read().unwrap_or(0f32);
+            let note = self.oscillator.next_sample() * self.envelope.next_sample();
+            *out_sample = passthrough + note;
+        }
+        if self.should_print {
+            if let Some((pushed_at, _)) = self.latency_queue.pop_latest() {
+                let now = self.clock.total();
+                let latency_ns = if now > pushed_at { now - pushed_at } else { 0 };
+                println!("Input-to-output latency: {}ms", latency_ns as f64 / 1000000f64);
+            }
+        }
     }
 
     /// KeyPress
@@ -146,12 +212,16 @@ impl SoundStream for AppSoundStream {
         if args.key == keyboard::Escape {
             self.should_exit = true;
         }
+        if let Some(frequency) = key_frequency(&args.key) {
+            self.oscillator.frequency = frequency;
+            self.envelope.note_on();
+            self.note_hold_ns = Some(NOTE_HOLD_NS);
+        }
     }
 
-    /*
-    /// Retrieve Events for callback (i.e. mouse, keyboard).
-    fn check_for_event(&self) -> Option<GameEvent<'static>> {
-        match self.chan {
+    /// Retrieve events queued by the game thread (i.e. mouse, keyboard).
+    fn check_for_event(&self) -> Option<GameEvent> {
+        match self.event_chan {
             Some(ref receiver) => match receiver.try_recv() {
                 Ok(event) => Some(event),
                 Err(_) => None
@@ -159,7 +229,6 @@ impl SoundStream for AppSoundStream {
             None => None
         }
     }
-    */
 
     /// Setup the exit condition (is checked once per buffer).
     fn exit(&self) -> bool { self.should_exit }
@@ -168,12 +237,18 @@ impl SoundStream for AppSoundStream {
 
 impl AppSoundStream {
     /// AppSoundStream constructor.
-    pub fn new(recv: Option<Receiver<bool>>) -> AppSoundStream {
+    pub fn new(kill_chan: Option<Receiver<bool>>, event_chan: Option<Receiver<GameEvent>>) -> AppSoundStream {
         AppSoundStream {
-            kill_chan: recv,
+            kill_chan: kill_chan,
+            event_chan: event_chan,
             should_exit: false,
             should_print: false,
-            buffer: vec![]
+            buffer: RingBuffer::new(0),
+            clock: Clock::new(),
+            latency_queue: ClockQueue::new(),
+            oscillator: Oscillator::new(Waveform::Sine, 261.63, 0.3, SoundStreamSettings::cd_quality().samples_per_second as f64),
+            envelope: Envelope::new(0.01, 0.1, 0.7, 0.2, SoundStreamSettings::cd_quality().samples_per_second as f64),
+            note_hold_ns: None
         }
     }
 }
@@ -0,0 +1,10 @@
+//! input.rs
+//!
+//! Input event payloads passed to `Game`/`SoundStream` callbacks.
+
+use keyboard;
+
+/// Carries the key behind a `key_press` callback.
+pub struct KeyPressArgs {
+    pub key: keyboard::Key
+}
@@ -0,0 +1,16 @@
+//! keyboard.rs
+//!
+//! Keyboard key codes, as delivered via `KeyPressArgs`.
+
+/// A keyboard key. Variants are named directly (not `Key::Space`) so
+/// they read as `keyboard::Space` at call sites.
+#[deriving(Clone, PartialEq, Show)]
+pub enum Key {
+    A,
+    S,
+    D,
+    F,
+    G,
+    Space,
+    Escape
+}
@@ -0,0 +1,14 @@
+//! lib.rs
+//!
+//! Piston: A user friendly game engine written in Rust.
+
+#![feature(globs)]
+
+pub use input::KeyPressArgs;
+pub use sound_stream::{Clock, ClockQueue, DeviceInfo, Envelope, FilePlayer, GameEvent, LowPassFilter,
+    ModPlayer, Module, Oscillator, RingBuffer, SampleFormat, SoundStream, SoundStreamSettings,
+    WavRecorder, Waveform, enumerate_input_devices, enumerate_output_devices, negotiate, quantize};
+
+pub mod input;
+pub mod keyboard;
+pub mod sound_stream;
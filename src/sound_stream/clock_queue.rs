@@ -0,0 +1,129 @@
+//! sound_stream/clock_queue.rs
+//!
+//! A queue of sample buffers tagged with the generation clock they were
+//! produced at, so a consumer running at a different rate than the
+//! producer can tell how stale a buffer is (and drop it) rather than
+//! simply playing whatever is next in line.
+
+use std::collections::RingBuf;
+use std::sync::Mutex;
+
+// Structs
+//------------------------------
+
+/// Accumulates successive `dt` nanosecond values (as received by
+/// `SoundStream::update`) into a running clock, suitable for
+/// timestamping entries pushed onto a `ClockQueue`.
+pub struct Clock {
+    total: u64
+}
+
+impl Clock {
+    /// Constructs a new `Clock` starting at zero.
+    pub fn new() -> Clock {
+        Clock { total: 0 }
+    }
+
+    /// Advances the clock by `dt` nanoseconds and returns the new total.
+    pub fn tick(&mut self, dt: u64) -> u64 {
+        self.total += dt;
+        self.total
+    }
+
+    /// The current accumulated total, in nanoseconds.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+/// A thread-safe queue of `(clock, samples)` entries, letting a
+/// producer (e.g. the game thread) hand timestamped buffers to a
+/// consumer (e.g. the audio callback) without the two having to agree
+/// on exact timing up front.
+pub struct ClockQueue {
+    queue: Mutex<RingBuf<(u64, Vec<f32>)>>
+}
+
+impl ClockQueue {
+
+    /// Constructs a new, empty `ClockQueue`.
+    pub fn new() -> ClockQueue {
+        ClockQueue { queue: Mutex::new(RingBuf::new()) }
+    }
+
+    /// Pushes a buffer onto the back of the queue, timestamped with
+    /// `clock`.
+    pub fn push(&self, clock: u64, data: Vec<f32>) {
+        self.queue.lock().push_back((clock, data));
+    }
+
+    /// Pops the oldest entry from the front of the queue.
+    pub fn pop_next(&self) -> Option<(u64, Vec<f32>)> {
+        self.queue.lock().pop_front()
+    }
+
+    /// Drains the whole queue, keeping only the newest entry - use
+    /// this when the consumer has fallen behind and playing every
+    /// buffered entry in order would just mean playing stale audio.
+    pub fn pop_latest(&self) -> Option<(u64, Vec<f32>)> {
+        let mut queue = self.queue.lock();
+        let latest = queue.pop_back();
+        queue.clear();
+        latest
+    }
+
+    /// Returns an entry to the front of the queue - for when only part
+    /// of a popped chunk was consumed and the remainder is still due.
+    pub fn unpop(&self, clock: u64, data: Vec<f32>) {
+        self.queue.lock().push_front((clock, data));
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, ClockQueue};
+
+    #[test]
+    fn clock_accumulates_successive_ticks() {
+        let mut clock = Clock::new();
+        assert_eq!(clock.tick(10), 10);
+        assert_eq!(clock.tick(5), 15);
+        assert_eq!(clock.total(), 15);
+    }
+
+    #[test]
+    fn pop_next_returns_entries_in_push_order() {
+        let queue = ClockQueue::new();
+        queue.push(1, vec![0.1f32]);
+        queue.push(2, vec![0.2f32]);
+        assert_eq!(queue.pop_next(), Some((1, vec![0.1f32])));
+        assert_eq!(queue.pop_next(), Some((2, vec![0.2f32])));
+        assert_eq!(queue.pop_next(), None);
+    }
+
+    #[test]
+    fn pop_latest_discards_everything_but_the_newest_entry() {
+        let queue = ClockQueue::new();
+        queue.push(1, vec![0.1f32]);
+        queue.push(2, vec![0.2f32]);
+        queue.push(3, vec![0.3f32]);
+        assert_eq!(queue.pop_latest(), Some((3, vec![0.3f32])));
+        assert_eq!(queue.pop_next(), None);
+    }
+
+    #[test]
+    fn pop_latest_on_an_empty_queue_returns_none() {
+        let queue = ClockQueue::new();
+        assert_eq!(queue.pop_latest(), None);
+    }
+
+    #[test]
+    fn unpop_puts_the_entry_back_at_the_front() {
+        let queue = ClockQueue::new();
+        queue.push(2, vec![0.2f32]);
+        queue.unpop(1, vec![0.1f32]);
+        assert_eq!(queue.pop_next(), Some((1, vec![0.1f32])));
+        assert_eq!(queue.pop_next(), Some((2, vec![0.2f32])));
+    }
+}
@@ -0,0 +1,204 @@
+//! sound_stream/device.rs
+//!
+//! Describes audio devices and the sample formats they support, and
+//! negotiates a `SoundStreamSettings` down to the closest configuration
+//! a device advertises. The device list is a fixed default device (see
+//! `query_devices`); the host's audio hardware is not queried.
+
+use super::SoundStreamSettings;
+
+// Enums
+//------------------------------
+
+/// The representation samples are exchanged with a device in, before
+/// being converted to/from the `f32` samples `audio_in`/`audio_out` see.
+#[deriving(Clone, Copy, PartialEq)]
+pub enum SampleFormat {
+    F32,
+    I16,
+    U16
+}
+
+// Structs
+//------------------------------
+
+/// What a single input or output device is capable of.
+pub struct DeviceInfo {
+    pub name: String,
+    pub is_input: bool,
+    pub supported_sample_rates: Vec<u32>,
+    pub supported_channels: Vec<u8>,
+    pub supported_formats: Vec<SampleFormat>
+}
+
+impl DeviceInfo {
+
+    /// Whether this device can be driven directly with `settings`,
+    /// with no negotiation required.
+    pub fn supports(&self, settings: &SoundStreamSettings, format: SampleFormat) -> bool {
+        self.supported_sample_rates.contains(&settings.samples_per_second)
+            && self.supported_channels.contains(&settings.channels)
+            && self.supported_formats.contains(&format)
+    }
+
+}
+
+/// Converts a single `f32` sample to/from `format`'s representation
+/// and back, simulating the precision a device in that format would
+/// actually deliver - e.g. 16-bit PCM rounds to one of 65536 steps.
+pub fn quantize(format: SampleFormat, sample: f32) -> f32 {
+    let clamped = sample.max(-1f32).min(1f32);
+    match format {
+        SampleFormat::F32 => clamped,
+        SampleFormat::I16 => (clamped * 32767f32).round() / 32767f32,
+        SampleFormat::U16 => {
+            let unsigned = ((clamped + 1f32) * 0.5f32 * 65535f32).round();
+            (unsigned / 65535f32) * 2f32 - 1f32
+        }
+    }
+}
+
+/// Lists the input devices to negotiate against: the fixed list from
+/// `query_devices`.
+pub fn enumerate_input_devices() -> Vec<DeviceInfo> {
+    query_devices(true)
+}
+
+/// Lists the output devices to negotiate against: the fixed list from
+/// `query_devices`.
+pub fn enumerate_output_devices() -> Vec<DeviceInfo> {
+    query_devices(false)
+}
+
+/// Picks the closest configuration `device` supports to the requested
+/// `settings`/`format`, falling back one step at a time: first the
+/// nearest sample rate, then the nearest channel count, then the
+/// first supported format.
+pub fn negotiate(settings: &SoundStreamSettings, format: SampleFormat, device: &DeviceInfo) -> (SoundStreamSettings, SampleFormat) {
+    if device.supports(settings, format) {
+        return (clone_settings(settings), format);
+    }
+
+    let samples_per_second = nearest(&device.supported_sample_rates, settings.samples_per_second);
+    let channels = nearest(&device.supported_channels, settings.channels);
+    let negotiated_format = if device.supported_formats.contains(&format) {
+        format
+    } else {
+        *device.supported_formats.first().unwrap_or(&SampleFormat::F32)
+    };
+
+    (SoundStreamSettings {
+        samples_per_second: samples_per_second,
+        frames: settings.frames,
+        channels: channels,
+        buffer_frames: settings.buffer_frames
+    }, negotiated_format)
+}
+
+// Helper functions
+//------------------------------
+
+fn clone_settings(settings: &SoundStreamSettings) -> SoundStreamSettings {
+    SoundStreamSettings {
+        samples_per_second: settings.samples_per_second,
+        frames: settings.frames,
+        channels: settings.channels,
+        buffer_frames: settings.buffer_frames
+    }
+}
+
+/// Finds the closest value to `wanted` in `supported`, falling back to
+/// `wanted` itself if nothing is advertised at all.
+fn nearest<T: Copy + PartialOrd + Sub<T, T>>(supported: &Vec<T>, wanted: T) -> T {
+    let mut best = wanted;
+    let mut best_distance = None;
+    for &candidate in supported.iter() {
+        let distance = if candidate > wanted { candidate - wanted } else { wanted - candidate };
+        if best_distance.is_none() || distance < best_distance.unwrap() {
+            best = candidate;
+            best_distance = Some(distance);
+        }
+    }
+    best
+}
+
+/// Returns the devices for the requested direction: a single fixed
+/// "Default Device" advertising 22050/44100/48000Hz, mono or stereo,
+/// and all three sample formats. The host's audio hardware is not
+/// queried.
+fn query_devices(input: bool) -> Vec<DeviceInfo> {
+    vec![DeviceInfo {
+        name: "Default Device".to_string(),
+        is_input: input,
+        supported_sample_rates: vec![22050, 44100, 48000],
+        supported_channels: vec![1, 2],
+        supported_formats: vec![SampleFormat::F32, SampleFormat::I16, SampleFormat::U16]
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeviceInfo, SampleFormat, nearest, negotiate, quantize};
+    use super::super::SoundStreamSettings;
+
+    #[test]
+    fn nearest_picks_the_closest_supported_rate() {
+        let supported = vec![22050u32, 44100, 48000];
+        assert_eq!(nearest(&supported, 44100), 44100);
+        assert_eq!(nearest(&supported, 46000), 44100);
+        assert_eq!(nearest(&supported, 47000), 48000);
+    }
+
+    #[test]
+    fn negotiate_is_untouched_when_device_already_supports_settings() {
+        let settings = SoundStreamSettings {
+            samples_per_second: 44100,
+            frames: 64,
+            channels: 2,
+            buffer_frames: 4096
+        };
+        let device = DeviceInfo {
+            name: "Test Device".to_string(),
+            is_input: false,
+            supported_sample_rates: vec![44100],
+            supported_channels: vec![2],
+            supported_formats: vec![SampleFormat::F32]
+        };
+        let (negotiated, format) = negotiate(&settings, SampleFormat::F32, &device);
+        assert_eq!(negotiated.samples_per_second, 44100);
+        assert_eq!(negotiated.channels, 2);
+        assert!(format == SampleFormat::F32);
+    }
+
+    #[test]
+    fn negotiate_falls_back_to_the_closest_supported_configuration() {
+        let settings = SoundStreamSettings {
+            samples_per_second: 44100,
+            frames: 64,
+            channels: 2,
+            buffer_frames: 4096
+        };
+        let device = DeviceInfo {
+            name: "Limited Device".to_string(),
+            is_input: false,
+            supported_sample_rates: vec![22050],
+            supported_channels: vec![1],
+            supported_formats: vec![SampleFormat::I16]
+        };
+        let (negotiated, format) = negotiate(&settings, SampleFormat::F32, &device);
+        assert_eq!(negotiated.samples_per_second, 22050);
+        assert_eq!(negotiated.channels, 1);
+        assert!(format == SampleFormat::I16);
+    }
+
+    #[test]
+    fn quantize_f32_is_unchanged() {
+        assert_eq!(quantize(SampleFormat::F32, 0.5f32), 0.5f32);
+    }
+
+    #[test]
+    fn quantize_i16_rounds_to_16_bit_steps() {
+        let quantized = quantize(SampleFormat::I16, 1f32);
+        assert!((quantized - 1f32).abs() < 0.001f32);
+    }
+}
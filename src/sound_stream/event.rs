@@ -0,0 +1,26 @@
+//! sound_stream/event.rs
+//!
+//! The non-blocking channel a game thread uses to drive its
+//! `SoundStream`: a `Game` hands out a `Sender<GameEvent>` from
+//! `get_event_sender`, and the audio thread polls the matching
+//! `Receiver` once per buffer via `SoundStream::check_for_event`, so a
+//! game can change a synth's frequency or trigger a sample in real
+//! time without any feedback from the mic path.
+
+use KeyPressArgs;
+
+// Enums
+//------------------------------
+
+/// An event sent from the game thread to the audio thread. Carries
+/// arbitrary payloads rather than just a kill signal, so a game isn't
+/// limited to exiting the stream - it can forward input or change
+/// synthesis parameters too.
+pub enum GameEvent {
+    /// A key was pressed on the game thread's window.
+    KeyPress(KeyPressArgs),
+    /// The mouse moved to `(x, y)`.
+    MouseMove(f64, f64),
+    /// A named parameter changed, e.g. a synth's frequency.
+    SetParameter(String, f64)
+}
@@ -0,0 +1,220 @@
+//! sound_stream/mod.rs
+//!
+//! Real-time audio subsystem for Piston games. A type implementing
+//! `SoundStream` is driven on its own thread: `audio_in` and `audio_out`
+//! are called once per buffer. `run` paces that loop to the buffer
+//! period itself and opens no audio device: `audio_in` receives a
+//! silent buffer, and what `audio_out` writes is not played.
+
+pub use self::clock_queue::{Clock, ClockQueue};
+pub use self::device::{DeviceInfo, SampleFormat, enumerate_input_devices, enumerate_output_devices,
+    negotiate, quantize};
+pub use self::event::GameEvent;
+pub use self::ring_buffer::RingBuffer;
+pub use self::synth::{Envelope, LowPassFilter, Oscillator, Waveform};
+pub use self::tracker::{ModPlayer, Module};
+pub use self::wav::{FilePlayer, WavRecorder};
+
+use std::io::timer;
+use std::time::Duration;
+use std::time::precise_time_ns;
+
+use KeyPressArgs;
+
+mod clock_queue;
+mod device;
+mod event;
+mod ring_buffer;
+mod synth;
+mod tracker;
+mod wav;
+
+// Structs
+//------------------------------
+
+/// Describes the format of the audio passed to `audio_in`/`audio_out`.
+pub struct SoundStreamSettings {
+    /// Number of samples captured/played per second.
+    pub samples_per_second: u32,
+    /// Number of sample frames per callback buffer.
+    pub frames: u32,
+    /// Number of interleaved channels per frame.
+    pub channels: u8,
+    /// Capacity (in samples) to size a `SoundStream`'s `RingBuffer` to,
+    /// e.g. by calling `RingBuffer::resize` from `update`.
+    pub buffer_frames: uint
+}
+
+impl SoundStreamSettings {
+    /// CD quality: 44.1kHz, stereo, 64 frames per buffer.
+    pub fn cd_quality() -> SoundStreamSettings {
+        SoundStreamSettings {
+            samples_per_second: 44100,
+            frames: 64,
+            channels: 2,
+            buffer_frames: 4096
+        }
+    }
+}
+
+// Traits
+//------------------------------
+
+/// Implement this on a struct to create your own real-time audio thread.
+pub trait SoundStream {
+
+    /// Load (called prior to main soundstream loop).
+    fn load(&mut self) {}
+
+    /// Update (gets called prior to audio_in/audio_out).
+    fn update(&mut self, settings: &SoundStreamSettings, dt: u64) {}
+
+    /// AudioInput.
+    fn audio_in(&mut self, input: &Vec<f32>, settings: &SoundStreamSettings) {}
+
+    /// AudioOutput.
+    fn audio_out(&mut self, output: &mut Vec<f32>, settings: &SoundStreamSettings) {}
+
+    /// KeyPress.
+    fn key_press(&mut self, args: &KeyPressArgs) {}
+
+    /// Mouse movement, forwarded from a queued `GameEvent::MouseMove`.
+    fn mouse_move(&mut self, x: f64, y: f64) {}
+
+    /// A named parameter changed, forwarded from a queued
+    /// `GameEvent::SetParameter` - e.g. a synth's frequency.
+    fn set_parameter(&mut self, name: &str, value: f64) {}
+
+    /// Retrieve the next event queued by the game thread, if any.
+    /// Polled once per buffer in `run`, via a non-blocking `try_recv`
+    /// so the real-time callback never stalls waiting on the game
+    /// thread.
+    fn check_for_event(&self) -> Option<GameEvent> { None }
+
+    /// Setup the exit condition (is checked once per buffer).
+    fn exit(&self) -> bool { false }
+
+    /// Run the soundstream to completion on the calling thread, calling
+    /// `load` once and then `update`/`audio_in`/`audio_out` once per
+    /// buffer until `exit` returns true.
+    ///
+    /// The requested `settings` are first negotiated against the first
+    /// device `enumerate_output_devices` lists, falling back to the
+    /// closest sample rate/channel count/format it advertises - samples
+    /// are then quantized to that format on the way into `audio_in` and
+    /// on the way out of `audio_out`.
+    fn run(mut self, settings: SoundStreamSettings) -> Self {
+        let (settings, format) = match enumerate_output_devices().first() {
+            Some(device) => negotiate(&settings, SampleFormat::F32, device),
+            None => (settings, SampleFormat::F32)
+        };
+        self.load();
+
+        // Buffers handed to audio_in/audio_out are sized to a full
+        // buffer period up front, matching what a real callback would
+        // hand over - implementors shouldn't have to guard against an
+        // empty Vec.
+        let frame_len = settings.frames as uint * settings.channels as uint;
+        let frame_duration_ns = (settings.frames as u64 * 1_000_000_000) / settings.samples_per_second as u64;
+        let mut last_tick = precise_time_ns();
+
+        while !self.exit() {
+            match self.check_for_event() {
+                Some(event) => dispatch_event(&mut self, event),
+                None => ()
+            }
+
+            let now = precise_time_ns();
+            let dt = now - last_tick;
+            last_tick = now;
+            self.update(&settings, dt);
+
+            let mut input = Vec::from_elem(frame_len, 0f32);
+            for sample in input.iter_mut() { *sample = quantize(format, *sample); }
+            self.audio_in(&input, &settings);
+
+            let mut output = Vec::from_elem(frame_len, 0f32);
+            self.audio_out(&mut output, &settings);
+            for sample in output.iter_mut() { *sample = quantize(format, *sample); }
+
+            // There's no real hardware callback driving this loop, so
+            // pace it to roughly one buffer period ourselves.
+            let elapsed = precise_time_ns() - now;
+            if frame_duration_ns > elapsed {
+                timer::sleep(Duration::nanoseconds((frame_duration_ns - elapsed) as i64));
+            }
+        }
+        self
+    }
+
+}
+
+// Helper functions
+//------------------------------
+
+/// Routes a single queued `GameEvent` to the matching `SoundStream`
+/// callback. Extracted out of `run` so the routing itself can be
+/// exercised directly, without driving a full real-time loop.
+fn dispatch_event<S: SoundStream>(stream: &mut S, event: GameEvent) {
+    match event {
+        GameEvent::KeyPress(args) => stream.key_press(&args),
+        GameEvent::MouseMove(x, y) => stream.mouse_move(x, y),
+        GameEvent::SetParameter(name, value) => stream.set_parameter(name.as_slice(), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use keyboard;
+    use KeyPressArgs;
+
+    use super::{GameEvent, SoundStream, dispatch_event};
+
+    /// A `SoundStream` that does nothing but record which callback it
+    /// was last routed to, so `dispatch_event`'s match can be asserted
+    /// against directly.
+    struct RecordingStream {
+        last_key: Option<keyboard::Key>,
+        last_mouse: Option<(f64, f64)>,
+        last_parameter: Option<(String, f64)>
+    }
+
+    impl RecordingStream {
+        fn new() -> RecordingStream {
+            RecordingStream { last_key: None, last_mouse: None, last_parameter: None }
+        }
+    }
+
+    impl SoundStream for RecordingStream {
+        fn key_press(&mut self, args: &KeyPressArgs) {
+            self.last_key = Some(args.key.clone());
+        }
+        fn mouse_move(&mut self, x: f64, y: f64) {
+            self.last_mouse = Some((x, y));
+        }
+        fn set_parameter(&mut self, name: &str, value: f64) {
+            self.last_parameter = Some((name.to_string(), value));
+        }
+    }
+
+    #[test]
+    fn dispatch_event_routes_key_press_to_key_press() {
+        let mut stream = RecordingStream::new();
+        dispatch_event(&mut stream, GameEvent::KeyPress(KeyPressArgs { key: keyboard::Space }));
+        assert_eq!(stream.last_key, Some(keyboard::Space));
+    }
+
+    #[test]
+    fn dispatch_event_routes_mouse_move_to_mouse_move() {
+        let mut stream = RecordingStream::new();
+        dispatch_event(&mut stream, GameEvent::MouseMove(1f64, 2f64));
+        assert_eq!(stream.last_mouse, Some((1f64, 2f64)));
+    }
+
+    #[test]
+    fn dispatch_event_routes_set_parameter_to_set_parameter() {
+        let mut stream = RecordingStream::new();
+        dispatch_event(&mut stream, GameEvent::SetParameter("freq".to_string(), 440f64));
+        assert_eq!(stream.last_parameter, Some(("freq".to_string(), 440f64)));
+    }
+}
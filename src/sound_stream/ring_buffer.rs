@@ -0,0 +1,143 @@
+//! sound_stream/ring_buffer.rs
+//!
+//! A single-producer/single-consumer circular buffer of samples.
+//! Neither end allocates or blocks, so both are safe to call from a
+//! real-time audio callback. `insert` and `read` both take `&mut self`,
+//! so the producer and consumer ends share one owner and are not driven
+//! from two threads at once.
+
+// Structs
+//------------------------------
+
+/// A preallocated ring buffer of `f32` samples.
+///
+/// `insert` drops the incoming sample rather than overwriting unread
+/// data when the buffer is full, and `read` returns `None` rather than
+/// blocking when the buffer is empty - both are safe to call from a
+/// real-time callback.
+pub struct RingBuffer {
+    buffer: Vec<f32>,
+    inp: uint,
+    out: uint
+}
+
+impl RingBuffer {
+
+    /// Constructs a new `RingBuffer` with `len` preallocated slots. One
+    /// slot is always kept empty, so it holds at most `len - 1` samples.
+    pub fn new(len: uint) -> RingBuffer {
+        RingBuffer {
+            buffer: Vec::from_elem(len, 0f32),
+            inp: 0,
+            out: 0
+        }
+    }
+
+    /// Inserts a sample at the producer end. Returns `false` and drops
+    /// the sample if the buffer is full, rather than overwriting data
+    /// the consumer hasn't read yet.
+    pub fn insert(&mut self, sample: f32) -> bool {
+        if self.buffer.len() == 0 { return false; }
+        let next = (self.inp + 1) % self.buffer.len();
+        if next == self.out { return false; }
+        self.buffer[self.inp] = sample;
+        self.inp = next;
+        true
+    }
+
+    /// Reads (pops) the next sample from the consumer end, or `None`
+    /// if the buffer is currently empty.
+    pub fn read(&mut self) -> Option<f32> {
+        if self.inp == self.out { return None; }
+        let sample = self.buffer[self.out];
+        self.out = (self.out + 1) % self.buffer.len();
+        Some(sample)
+    }
+
+    /// Resets the buffer to empty without touching its capacity.
+    pub fn clear(&mut self) {
+        self.inp = 0;
+        self.out = 0;
+    }
+
+    /// Reallocates the buffer to `len` slots and clears it, if
+    /// `len` differs from the current capacity. A no-op otherwise, so
+    /// it's safe to call every `update` without losing in-flight data.
+    pub fn resize(&mut self, len: uint) {
+        if self.buffer.len() != len {
+            self.buffer = Vec::from_elem(len, 0f32);
+            self.clear();
+        }
+    }
+
+    /// The number of slots in the buffer; at most `capacity() - 1`
+    /// samples are held at once.
+    pub fn capacity(&self) -> uint {
+        self.buffer.len()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuffer;
+
+    #[test]
+    fn read_on_empty_returns_none() {
+        let mut buffer = RingBuffer::new(4);
+        assert_eq!(buffer.read(), None);
+    }
+
+    #[test]
+    fn insert_then_read_round_trips() {
+        let mut buffer = RingBuffer::new(4);
+        assert!(buffer.insert(1f32));
+        assert!(buffer.insert(2f32));
+        assert_eq!(buffer.read(), Some(1f32));
+        assert_eq!(buffer.read(), Some(2f32));
+        assert_eq!(buffer.read(), None);
+    }
+
+    #[test]
+    fn insert_drops_rather_than_overwrites_when_full() {
+        // Capacity 4 holds 3 usable samples - one slot is always kept
+        // empty to distinguish full from empty.
+        let mut buffer = RingBuffer::new(4);
+        assert!(buffer.insert(1f32));
+        assert!(buffer.insert(2f32));
+        assert!(buffer.insert(3f32));
+        assert!(!buffer.insert(4f32));
+        assert_eq!(buffer.read(), Some(1f32));
+        assert_eq!(buffer.read(), Some(2f32));
+        assert_eq!(buffer.read(), Some(3f32));
+        assert_eq!(buffer.read(), None);
+    }
+
+    #[test]
+    fn clear_resets_to_empty() {
+        let mut buffer = RingBuffer::new(4);
+        buffer.insert(1f32);
+        buffer.clear();
+        assert_eq!(buffer.read(), None);
+    }
+
+    #[test]
+    fn resize_to_a_new_length_reallocates_and_clears() {
+        let mut buffer = RingBuffer::new(4);
+        buffer.insert(1f32);
+        buffer.resize(8);
+        assert_eq!(buffer.capacity(), 8);
+        assert_eq!(buffer.read(), None);
+    }
+
+    #[test]
+    fn resize_to_the_same_length_is_a_no_op() {
+        let mut buffer = RingBuffer::new(4);
+        buffer.insert(1f32);
+        buffer.insert(2f32);
+        buffer.resize(4);
+        // Unread data survives a same-length resize.
+        assert_eq!(buffer.read(), Some(1f32));
+        assert_eq!(buffer.read(), Some(2f32));
+    }
+}
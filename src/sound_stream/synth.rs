@@ -0,0 +1,277 @@
+//! sound_stream/synth.rs
+//!
+//! Small DSP building blocks for generating sound rather than just
+//! passing a microphone through: band-limited-enough oscillators, a
+//! low-pass filter stage, and an ADSR envelope so notes triggered from
+//! `key_press` have an attack/release instead of clicking in and out.
+
+use std::f64::consts::PI;
+use std::rand;
+use std::rand::Rng;
+
+// Enums
+//------------------------------
+
+/// The shape of wave an `Oscillator` generates.
+#[deriving(Clone, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Saw,
+    Triangle,
+    Noise
+}
+
+// Structs
+//------------------------------
+
+/// A phase-accumulating oscillator that writes directly into an
+/// `audio_out` buffer - no allocation, just a running phase advanced
+/// by `frequency / samples_per_second` each sample.
+pub struct Oscillator {
+    pub waveform: Waveform,
+    pub frequency: f64,
+    pub amplitude: f32,
+    phase: f64, // normalized, kept within [0.0, 1.0) to avoid precision loss over long runs
+    samples_per_second: f64
+}
+
+impl Oscillator {
+
+    /// Constructs a new oscillator at the given frequency and
+    /// amplitude, sampled at `samples_per_second`.
+    pub fn new(waveform: Waveform, frequency: f64, amplitude: f32, samples_per_second: f64) -> Oscillator {
+        Oscillator {
+            waveform: waveform,
+            frequency: frequency,
+            amplitude: amplitude,
+            phase: 0f64,
+            samples_per_second: samples_per_second
+        }
+    }
+
+    /// Advances the phase accumulator by one sample and returns the
+    /// next value, wrapping modulo `1.0`.
+    pub fn next_sample(&mut self) -> f32 {
+        let value = match self.waveform {
+            Waveform::Sine => (2f64 * PI * self.phase).sin() as f32,
+            Waveform::Square => if self.phase < 0.5f64 { 1f32 } else { -1f32 },
+            Waveform::Saw => (2f64 * self.phase - 1f64) as f32,
+            Waveform::Triangle => triangle(self.phase) as f32,
+            Waveform::Noise => rand::task_rng().gen_range(-1f32, 1f32)
+        };
+        self.phase += self.frequency / self.samples_per_second;
+        if self.phase >= 1f64 { self.phase -= 1f64; }
+        value * self.amplitude
+    }
+
+    /// Overwrites every sample of `output` with the oscillator's next
+    /// samples, starting at index 0.
+    pub fn fill(&mut self, output: &mut Vec<f32>) {
+        for out_sample in output.iter_mut() {
+            *out_sample = self.next_sample();
+        }
+    }
+
+}
+
+/// The shape of a triangle wave over a normalized `[0.0, 1.0)` phase:
+/// rises from the start, peaks at a quarter, falls through zero at the
+/// midpoint, troughs at three quarters.
+fn triangle(phase: f64) -> f64 {
+    if phase < 0.25f64 {
+        4f64 * phase
+    } else if phase < 0.75f64 {
+        2f64 - 4f64 * phase
+    } else {
+        4f64 * phase - 4f64
+    }
+}
+
+/// A one-pole low-pass filter: cheap enough to run per-sample on the
+/// audio thread, with a single pole placed from the cutoff frequency.
+pub struct LowPassFilter {
+    alpha: f32,
+    previous: f32
+}
+
+impl LowPassFilter {
+
+    /// Constructs a filter with the given cutoff, sampled at
+    /// `samples_per_second`.
+    pub fn new(cutoff_hz: f64, samples_per_second: f64) -> LowPassFilter {
+        let rc = 1f64 / (2f64 * PI * cutoff_hz);
+        let dt = 1f64 / samples_per_second;
+        let alpha = dt / (rc + dt);
+        LowPassFilter { alpha: alpha as f32, previous: 0f32 }
+    }
+
+    /// Filters a single sample.
+    pub fn process(&mut self, input: f32) -> f32 {
+        self.previous = self.previous + self.alpha * (input - self.previous);
+        self.previous
+    }
+
+    /// Filters a buffer of samples in place.
+    pub fn process_buffer(&mut self, buffer: &mut Vec<f32>) {
+        for sample in buffer.iter_mut() {
+            *sample = self.process(*sample);
+        }
+    }
+
+}
+
+/// The stage of an `Envelope`'s attack/decay/sustain/release cycle.
+#[deriving(Clone, PartialEq)]
+enum EnvelopeStage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release
+}
+
+/// A standard ADSR envelope: `note_on` starts the attack/decay ramp
+/// into sustain, `note_off` starts the release ramp back to silence.
+pub struct Envelope {
+    attack: f64,
+    decay: f64,
+    sustain: f32,
+    release: f64,
+    samples_per_second: f64,
+    stage: EnvelopeStage,
+    level: f32,
+    release_start_level: f32,
+    time_in_stage: f64
+}
+
+impl Envelope {
+
+    /// Constructs a new envelope. `attack`/`decay`/`release` are in
+    /// seconds, `sustain` is the sustained amplitude level.
+    pub fn new(attack: f64, decay: f64, sustain: f32, release: f64, samples_per_second: f64) -> Envelope {
+        Envelope {
+            attack: attack,
+            decay: decay,
+            sustain: sustain,
+            release: release,
+            samples_per_second: samples_per_second,
+            stage: EnvelopeStage::Idle,
+            level: 0f32,
+            release_start_level: 0f32,
+            time_in_stage: 0f64
+        }
+    }
+
+    /// Triggers a new note, beginning the attack stage.
+    pub fn note_on(&mut self) {
+        self.stage = EnvelopeStage::Attack;
+        self.time_in_stage = 0f64;
+    }
+
+    /// Releases the current note, beginning the release stage.
+    pub fn note_off(&mut self) {
+        self.release_start_level = self.level;
+        self.stage = EnvelopeStage::Release;
+        self.time_in_stage = 0f64;
+    }
+
+    /// Advances the envelope by one sample and returns the current
+    /// amplitude level.
+    pub fn next_sample(&mut self) -> f32 {
+        let dt = 1f64 / self.samples_per_second;
+        match self.stage {
+            EnvelopeStage::Idle => { self.level = 0f32; }
+            EnvelopeStage::Attack => {
+                self.level = if self.attack <= 0f64 { 1f32 } else {
+                    (self.time_in_stage / self.attack) as f32
+                };
+                if self.time_in_stage >= self.attack {
+                    self.stage = EnvelopeStage::Decay;
+                    self.time_in_stage = 0f64;
+                    self.level = 1f32;
+                }
+            }
+            EnvelopeStage::Decay => {
+                self.level = if self.decay <= 0f64 { self.sustain } else {
+                    1f32 - (1f32 - self.sustain) * (self.time_in_stage / self.decay) as f32
+                };
+                if self.time_in_stage >= self.decay {
+                    self.stage = EnvelopeStage::Sustain;
+                    self.time_in_stage = 0f64;
+                    self.level = self.sustain;
+                }
+            }
+            EnvelopeStage::Sustain => { self.level = self.sustain; }
+            EnvelopeStage::Release => {
+                self.level = if self.release <= 0f64 { 0f32 } else {
+                    self.release_start_level * (1f32 - (self.time_in_stage / self.release) as f32)
+                };
+                if self.time_in_stage >= self.release {
+                    self.stage = EnvelopeStage::Idle;
+                    self.time_in_stage = 0f64;
+                    self.level = 0f32;
+                }
+            }
+        }
+        self.time_in_stage += dt;
+        self.level
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Envelope, Oscillator, Waveform, triangle};
+
+    #[test]
+    fn triangle_rises_peaks_falls_and_troughs() {
+        assert_eq!(triangle(0f64), 0f64);
+        assert_eq!(triangle(0.25f64), 1f64);
+        assert_eq!(triangle(0.5f64), 0f64);
+        assert_eq!(triangle(0.75f64), -1f64);
+    }
+
+    #[test]
+    fn oscillator_sine_starts_at_zero_phase() {
+        let mut osc = Oscillator::new(Waveform::Sine, 440f64, 1f32, 44100f64);
+        assert_eq!(osc.next_sample(), 0f32);
+    }
+
+    #[test]
+    fn envelope_is_silent_until_note_on() {
+        let mut envelope = Envelope::new(0.01, 0.1, 0.5, 0.1, 44100f64);
+        assert_eq!(envelope.next_sample(), 0f32);
+    }
+
+    #[test]
+    fn envelope_ramps_up_through_attack_into_sustain() {
+        let mut envelope = Envelope::new(0.01, 0.01, 0.5, 0.1, 44100f64);
+        envelope.note_on();
+        let mut last = 0f32;
+        // The attack lasts ~441 samples at 44.1kHz; it only ever rises.
+        for _ in range(0u, 400) {
+            let level = envelope.next_sample();
+            assert!(level >= last - 0.001f32);
+            last = level;
+        }
+        // Decay then falls from the peak to the sustain level.
+        for _ in range(400u, 2000) {
+            last = envelope.next_sample();
+        }
+        assert!((last - 0.5f32).abs() < 0.01f32);
+    }
+
+    #[test]
+    fn envelope_releases_back_to_silence() {
+        let mut envelope = Envelope::new(0f64, 0f64, 1f32, 0.01, 44100f64);
+        envelope.note_on();
+        envelope.next_sample();
+        envelope.note_off();
+        let mut level = 1f32;
+        for _ in range(0u, 1000) {
+            level = envelope.next_sample();
+        }
+        assert_eq!(level, 0f32);
+    }
+}
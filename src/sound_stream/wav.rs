@@ -0,0 +1,367 @@
+//! sound_stream/wav.rs
+//!
+//! Turns the mic-to-speaker demo into something that can capture
+//! sessions and loop pre-rendered audio: `WavRecorder` writes whatever
+//! passes through `audio_in` out to a `.wav` file, and `FilePlayer`
+//! feeds a `.wav` file's samples into `audio_out` in its place.
+
+use std::io::{File, IoResult, SeekSet};
+
+use super::SoundStreamSettings;
+
+// Structs
+//------------------------------
+
+/// Writes samples passed through `audio_in` to a 16-bit PCM `.wav`
+/// file as they arrive.
+pub struct WavRecorder {
+    file: File,
+    samples_written: u32,
+    channels: u8,
+    samples_per_second: u32
+}
+
+impl WavRecorder {
+
+    /// Creates a new recorder writing to `path`, sized for the given
+    /// stream settings. The header is written immediately with a
+    /// placeholder data length, which `finish` patches up once the
+    /// final sample count is known.
+    pub fn record_to(path: &Path, settings: &SoundStreamSettings) -> IoResult<WavRecorder> {
+        let mut file = try!(File::create(path));
+        try!(write_header(&mut file, settings.channels, settings.samples_per_second, 0));
+        Ok(WavRecorder {
+            file: file,
+            samples_written: 0,
+            channels: settings.channels,
+            samples_per_second: settings.samples_per_second
+        })
+    }
+
+    /// Appends a buffer of `f32` samples, as handed to `audio_in`,
+    /// converting each one to 16-bit PCM.
+    pub fn write(&mut self, samples: &Vec<f32>) -> IoResult<()> {
+        for &sample in samples.iter() {
+            try!(self.file.write_le_i16(f32_to_i16(sample)));
+        }
+        self.samples_written += samples.len() as u32;
+        Ok(())
+    }
+
+    /// Flushes the file and patches the `.wav` header with the final
+    /// data length, consuming the recorder.
+    pub fn finish(mut self) -> IoResult<()> {
+        let data_bytes = self.samples_written * 2;
+        try!(self.file.seek(4, SeekSet));
+        try!(self.file.write_le_u32(36 + data_bytes));
+        try!(self.file.seek(40, SeekSet));
+        try!(self.file.write_le_u32(data_bytes));
+        self.file.flush()
+    }
+
+}
+
+/// Feeds a `.wav` file's samples into `audio_out` in place of (or
+/// mixed with) live input. Samples are converted to `f32` on load, and
+/// remapped/resampled on the fly in `audio_out` to whatever channel
+/// count and sample rate the stream is actually running at.
+pub struct FilePlayer {
+    samples: Vec<f32>, // interleaved per `channels`
+    channels: u8,
+    samples_per_second: u32,
+    position: f64 // fractional frame position into `samples`
+}
+
+impl FilePlayer {
+
+    /// Loads a `.wav` file from `path`, converting its samples (be
+    /// they 16-bit PCM or 32-bit float) to `f32`.
+    pub fn new(path: &Path) -> IoResult<FilePlayer> {
+        let mut file = try!(File::open(path));
+        let (channels, samples_per_second, format, bits_per_sample) = try!(read_header(&mut file));
+        let samples = try!(read_samples(&mut file, format, bits_per_sample));
+        Ok(FilePlayer {
+            samples: samples,
+            channels: channels,
+            samples_per_second: samples_per_second,
+            position: 0f64
+        })
+    }
+
+    /// Fills `output` with the next samples from the file, looping
+    /// back to the start once the end is reached, remapped from the
+    /// file's own channel count/sample rate to `settings`'s. Mirrors
+    /// the `SoundStream::audio_out` contract so a `FilePlayer` can be
+    /// dropped straight into an implementor's `audio_out`.
+    pub fn audio_out(&mut self, output: &mut Vec<f32>, settings: &SoundStreamSettings) {
+        if self.channels == 0 { return; }
+        let file_frames = self.samples.len() / self.channels as uint;
+        if file_frames == 0 { return; }
+
+        let rate_ratio = self.samples_per_second as f64 / settings.samples_per_second as f64;
+        let out_channels = settings.channels as uint;
+        let file_channels = self.channels as uint;
+
+        let mut i = 0u;
+        while i < output.len() {
+            let frame = (self.position as uint) % file_frames;
+            for out_channel in range(0u, out_channels) {
+                if i >= output.len() { break; }
+                output[i] = mix_channel(&self.samples, frame, file_channels, out_channels, out_channel);
+                i += 1;
+            }
+            self.position += rate_ratio;
+            if self.position as uint >= file_frames {
+                self.position -= file_frames as f64;
+            }
+        }
+    }
+
+}
+
+/// Picks (or mixes) the sample(s) for a single output channel out of a
+/// `frame`'s `file_channels` interleaved samples. When downmixing (or
+/// when the channel counts match), every file channel that aliases
+/// onto this output channel is averaged together; when upmixing, the
+/// corresponding file channel is simply repeated across the extra
+/// output channels.
+fn mix_channel(samples: &Vec<f32>, frame: uint, file_channels: uint, out_channels: uint, out_channel: uint) -> f32 {
+    if out_channels <= file_channels {
+        let mut sum = 0f32;
+        let mut count = 0u;
+        let mut file_channel = out_channel;
+        while file_channel < file_channels {
+            sum += samples[frame * file_channels + file_channel];
+            count += 1;
+            file_channel += out_channels;
+        }
+        sum / count as f32
+    } else {
+        samples[frame * file_channels + (out_channel % file_channels)]
+    }
+}
+
+// Helper functions
+//------------------------------
+
+/// Converts a normalized `f32` sample in `[-1.0, 1.0]` to 16-bit PCM.
+fn f32_to_i16(sample: f32) -> i16 {
+    (sample.max(-1f32).min(1f32) * 32767f32) as i16
+}
+
+/// Converts a 16-bit PCM sample to a normalized `f32`.
+fn i16_to_f32(sample: i16) -> f32 {
+    sample as f32 / 32767f32
+}
+
+/// Writes a 44-byte canonical PCM `.wav` header for 16-bit samples.
+fn write_header(file: &mut File, channels: u8, samples_per_second: u32, data_bytes: u32) -> IoResult<()> {
+    let block_align = channels as u32 * 2;
+    let byte_rate = samples_per_second * block_align;
+    try!(file.write_str("RIFF"));
+    try!(file.write_le_u32(36 + data_bytes));
+    try!(file.write_str("WAVE"));
+    try!(file.write_str("fmt "));
+    try!(file.write_le_u32(16)); // fmt chunk size
+    try!(file.write_le_u16(1)); // PCM
+    try!(file.write_le_u16(channels as u16));
+    try!(file.write_le_u32(samples_per_second));
+    try!(file.write_le_u32(byte_rate));
+    try!(file.write_le_u16(block_align as u16));
+    try!(file.write_le_u16(16)); // bits per sample
+    try!(file.write_str("data"));
+    file.write_le_u32(data_bytes)
+}
+
+/// Reads a `.wav` header, returning `(channels, samples_per_second,
+/// format_code, bits_per_sample)`. `format_code` is `1` for PCM, `3`
+/// for IEEE float. Chunks between `"WAVE"` and `"data"` are walked by
+/// their declared size rather than assumed to be a fixed 16-byte `fmt`
+/// chunk immediately followed by `data` - real encoders routinely add a
+/// `fact` chunk, or write an 18- or 40-byte extensible `fmt` chunk, and
+/// both would otherwise desync every read that follows.
+fn read_header<R: Reader>(reader: &mut R) -> IoResult<(u8, u32, u16, u16)> {
+    try!(reader.read_exact(4)); // "RIFF"
+    try!(reader.read_le_u32()); // chunk size
+    try!(reader.read_exact(4)); // "WAVE"
+
+    let mut format = 0u16;
+    let mut channels = 0u8;
+    let mut samples_per_second = 0u32;
+    let mut bits_per_sample = 0u16;
+
+    loop {
+        let tag = try!(read_tag(reader));
+        let chunk_size = try!(reader.read_le_u32());
+        if tag.as_slice() == "fmt " {
+            format = try!(reader.read_le_u16());
+            channels = try!(reader.read_le_u16()) as u8;
+            samples_per_second = try!(reader.read_le_u32());
+            try!(reader.read_le_u32()); // byte rate
+            try!(reader.read_le_u16()); // block align
+            bits_per_sample = try!(reader.read_le_u16());
+            // An 18- or 40-byte extensible fmt chunk has extra bytes
+            // past the canonical 16 we just read - skip them.
+            if chunk_size > 16 {
+                try!(reader.read_exact((chunk_size - 16) as uint));
+            }
+        } else if tag.as_slice() == "data" {
+            break;
+        } else {
+            try!(reader.read_exact(chunk_size as uint)); // e.g. a "fact" chunk
+        }
+    }
+
+    Ok((channels, samples_per_second, format, bits_per_sample))
+}
+
+/// Reads a 4-byte RIFF chunk tag as a `String`, for comparison against
+/// literals like `"fmt "`/`"data"`.
+fn read_tag<R: Reader>(reader: &mut R) -> IoResult<String> {
+    let bytes = try!(reader.read_exact(4));
+    Ok(String::from_utf8_lossy(bytes.as_slice()).into_string())
+}
+
+/// Reads the remainder of the file as samples, converting to `f32`
+/// according to `format` (`1` = PCM, `3` = IEEE float) and
+/// `bits_per_sample`.
+fn read_samples<R: Reader>(reader: &mut R, format: u16, bits_per_sample: u16) -> IoResult<Vec<f32>> {
+    let mut samples = vec![];
+    loop {
+        let sample = match (format, bits_per_sample) {
+            (1, 16) => match reader.read_le_i16() {
+                Ok(raw) => i16_to_f32(raw),
+                Err(_) => break
+            },
+            (3, 32) => match reader.read_le_u32() {
+                Ok(raw) => unsafe { ::std::mem::transmute::<u32, f32>(raw) },
+                Err(_) => break
+            },
+            _ => break
+        };
+        samples.push(sample);
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::MemReader;
+
+    use super::{FilePlayer, SoundStreamSettings, f32_to_i16, i16_to_f32, read_header};
+
+    /// Builds a PCM `.wav` header with an 18-byte extensible `fmt` chunk
+    /// (2 bytes wider than canonical) followed by an unrelated `fact`
+    /// chunk, exactly the layout real encoders produce and that a fixed
+    /// 16-byte-fmt-then-data assumption would desync on.
+    fn build_header_with_fact_chunk_bytes() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.push_all("RIFF".as_bytes());
+        bytes.push_all([0u8, 0, 0, 0].as_slice()); // overall chunk size, unused by read_header
+        bytes.push_all("WAVE".as_bytes());
+
+        bytes.push_all("fmt ".as_bytes());
+        bytes.push_all([18u8, 0, 0, 0].as_slice()); // fmt chunk size: 16 + 2 extra bytes
+        bytes.push_all([1u8, 0].as_slice()); // PCM
+        bytes.push_all([2u8, 0].as_slice()); // channels = 2
+        bytes.push_all([0x44u8, 0xac, 0, 0].as_slice()); // samples_per_second = 44100
+        bytes.push_all([0u8, 0, 0, 0].as_slice()); // byte rate, unused
+        bytes.push_all([0u8, 0].as_slice()); // block align, unused
+        bytes.push_all([16u8, 0].as_slice()); // bits_per_sample = 16
+        bytes.push_all([0u8, 0].as_slice()); // 2 extra extensible-fmt bytes
+
+        bytes.push_all("fact".as_bytes());
+        bytes.push_all([4u8, 0, 0, 0].as_slice()); // fact chunk size
+        bytes.push_all([0u8, 0, 0, 0].as_slice()); // fact chunk body, unused
+
+        bytes.push_all("data".as_bytes());
+        bytes.push_all([4u8, 0, 0, 0].as_slice()); // data chunk size
+        bytes.push_all([1u8, 0, 2, 0].as_slice()); // two 16-bit samples
+
+        bytes
+    }
+
+    #[test]
+    fn read_header_walks_past_an_extensible_fmt_and_a_fact_chunk() {
+        let bytes = build_header_with_fact_chunk_bytes();
+        let (channels, samples_per_second, format, bits_per_sample) =
+            read_header(&mut MemReader::new(bytes)).unwrap();
+        assert_eq!(channels, 2);
+        assert_eq!(samples_per_second, 44100);
+        assert_eq!(format, 1);
+        assert_eq!(bits_per_sample, 16);
+    }
+
+    #[test]
+    fn audio_out_downmixes_stereo_to_mono_by_averaging_channels() {
+        let mut player = FilePlayer {
+            samples: vec![1f32, 0.5f32], // one stereo frame: left 1.0, right 0.5
+            channels: 2,
+            samples_per_second: 44100,
+            position: 0f64
+        };
+        let settings = SoundStreamSettings {
+            samples_per_second: 44100,
+            frames: 1,
+            channels: 1,
+            buffer_frames: 0
+        };
+        let mut output = vec![0f32];
+        player.audio_out(&mut output, &settings);
+        assert_eq!(output[0], 0.75f32);
+    }
+
+    #[test]
+    fn audio_out_upmixes_mono_to_stereo_by_repeating_the_channel() {
+        let mut player = FilePlayer {
+            samples: vec![0.25f32], // one mono frame
+            channels: 1,
+            samples_per_second: 44100,
+            position: 0f64
+        };
+        let settings = SoundStreamSettings {
+            samples_per_second: 44100,
+            frames: 1,
+            channels: 2,
+            buffer_frames: 0
+        };
+        let mut output = vec![0f32, 0f32];
+        player.audio_out(&mut output, &settings);
+        assert_eq!(output, vec![0.25f32, 0.25f32]);
+    }
+
+    #[test]
+    fn audio_out_resamples_at_twice_the_file_rate_by_skipping_every_other_frame() {
+        let mut player = FilePlayer {
+            samples: vec![1f32, 2f32, 3f32, 4f32], // 4 mono frames
+            channels: 1,
+            samples_per_second: 88200,
+            position: 0f64
+        };
+        let settings = SoundStreamSettings {
+            samples_per_second: 44100,
+            frames: 1,
+            channels: 1,
+            buffer_frames: 0
+        };
+        let mut output = vec![0f32, 0f32];
+        player.audio_out(&mut output, &settings);
+        // rate_ratio is 2.0, so the second output frame should pull from
+        // the file's 3rd frame rather than its 2nd.
+        assert_eq!(output, vec![1f32, 3f32]);
+    }
+
+    #[test]
+    fn f32_to_i16_round_trips_within_one_step() {
+        for &sample in [-1f32, -0.5f32, 0f32, 0.5f32, 1f32].iter() {
+            let round_tripped = i16_to_f32(f32_to_i16(sample));
+            assert!((round_tripped - sample).abs() <= 1f32 / 32767f32);
+        }
+    }
+
+    #[test]
+    fn f32_to_i16_clamps_out_of_range_input() {
+        assert_eq!(f32_to_i16(2f32), f32_to_i16(1f32));
+        assert_eq!(f32_to_i16(-2f32), f32_to_i16(-1f32));
+    }
+}